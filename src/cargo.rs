@@ -0,0 +1,147 @@
+//! Thin wrappers around the bits of `cargo` xargo shells out to and reads
+//! configuration from: the crate root, the `cargo` invocation itself, and
+//! the `[build]`/`[target.<triple>]` tables of `.cargo/config(.toml)`.
+
+use std::env;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use toml::Value;
+
+use errors::*;
+use xargo::Home;
+
+/// The crate root xargo was invoked from.
+pub struct Root {
+    path: PathBuf,
+}
+
+impl Root {
+    pub fn new(path: PathBuf) -> Root {
+        Root { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// The parsed content of the nearest `.cargo/config(.toml)`.
+pub struct Config {
+    table: Value,
+}
+
+impl Config {
+    pub fn new(table: Value) -> Config {
+        Config { table }
+    }
+
+    /// Resolves `key` (`rustflags` or `rustdocflags`) the way cargo does:
+    /// `target.<triple>.<key>` if present, else `build.<key>`.
+    fn flags(&self, triple: &str, key: &str) -> Vec<String> {
+        self.table
+            .lookup(&format!("target.{}.{}", triple, key))
+            .or_else(|| self.table.lookup(&format!("build.{}", key)))
+            .and_then(Value::as_slice)
+            .map(|flags| {
+                flags
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(str::to_owned)
+                    .collect()
+            })
+            .unwrap_or_else(Vec::new)
+    }
+}
+
+/// Which cargo subcommand xargo was invoked as, as far as xargo needs to
+/// distinguish them.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Subcommand {
+    Build,
+    Doc,
+    Other,
+}
+
+/// A `RUSTFLAGS`/`RUSTDOCFLAGS` value being assembled from possibly several
+/// sources (`.cargo/config`, `Xargo.toml`, xargo itself) before it's
+/// rendered to the single string the child `cargo` process sees.
+pub struct Rustflags(Vec<String>);
+
+impl Rustflags {
+    pub fn new(flags: Vec<String>) -> Rustflags {
+        Rustflags(flags)
+    }
+
+    /// Appends `other`'s flags after this one's, in cargo's own precedence
+    /// order (earlier sources first, later sources able to override them),
+    /// skipping any flag already present so the same flag set in multiple
+    /// sources (e.g. both `.cargo/config`'s `build.rustflags` and the
+    /// `RUSTFLAGS` env var) isn't passed to rustc twice. A flag is an option
+    /// token together with the value tokens after it (`-C opt-level=3`).
+    pub fn extend(mut self, other: Rustflags) -> Rustflags {
+        fn split(flags: &[String]) -> Vec<Vec<String>> {
+            let mut out: Vec<Vec<String>> = Vec::new();
+            for token in flags {
+                if token.starts_with('-') || out.is_empty() {
+                    out.push(vec![token.clone()]);
+                } else {
+                    out.last_mut().unwrap().push(token.clone());
+                }
+            }
+            out
+        }
+
+        let mut present = split(&self.0);
+        for flag in split(&other.0) {
+            if !present.contains(&flag) {
+                self.0.extend(flag.iter().cloned());
+                present.push(flag);
+            }
+        }
+        self
+    }
+
+    /// The flags inherited from `key`'s environment variable (`RUSTFLAGS`
+    /// or `RUSTDOCFLAGS`), if any, split on whitespace the same way cargo
+    /// itself splits them.
+    pub fn from_env(key: &str) -> Rustflags {
+        let flags = env::var(key)
+            .map(|raw| raw.split_whitespace().map(str::to_owned).collect())
+            .unwrap_or_default();
+
+        Rustflags::new(flags)
+    }
+
+    /// Renders the accumulated flags to a single space-separated
+    /// `OsString`, after appending the `--sysroot` xargo needs the child
+    /// `cargo`/`rustc` invocation to use.
+    pub fn for_xargo(mut self, home: &Home) -> OsString {
+        self.0.push("--sysroot".to_owned());
+        self.0.push(home.display().to_string());
+
+        OsString::from(self.0.join(" "))
+    }
+}
+
+/// Resolves the `RUSTFLAGS` cargo itself would use for `triple`, from
+/// `.cargo/config`'s `build.rustflags`/`target.<triple>.rustflags`.
+pub fn rustflags(config: Option<&Config>, triple: &str) -> Result<Rustflags> {
+    Ok(Rustflags::new(
+        config.map(|c| c.flags(triple, "rustflags")).unwrap_or_default(),
+    ))
+}
+
+/// Resolves the `RUSTDOCFLAGS` cargo itself would use for `triple`, from
+/// `.cargo/config`'s `build.rustdocflags`/`target.<triple>.rustdocflags`.
+pub fn rustdocflags(config: Option<&Config>, triple: &str) -> Result<Rustflags> {
+    Ok(Rustflags::new(
+        config.map(|c| c.flags(triple, "rustdocflags")).unwrap_or_default(),
+    ))
+}
+
+/// Starts building the `cargo` invocation xargo wraps.
+pub fn command() -> Command {
+    Command::new(env::var_os("CARGO").unwrap_or_else(|| "cargo".into()))
+}
@@ -0,0 +1,114 @@
+//! xargo's error type. Built on `error_chain` like the rest of the crate,
+//! but with a handful of concrete, downcastable variants for conditions the
+//! CLI wants to render and exit differently for: a missing `Xargo.toml`, an
+//! unparseable one, and a `rust-src` that couldn't be resolved at all.
+
+use std::fmt;
+use std::path::PathBuf;
+
+/// `Xargo.toml` wasn't found in `searched_at` or any of its parents.
+#[derive(Debug)]
+pub struct XargoTomlNotFound {
+    pub searched_at: PathBuf,
+}
+
+impl fmt::Display for XargoTomlNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "could not find `Xargo.toml` in `{}` or any parent directory",
+            self.searched_at.display()
+        )
+    }
+}
+
+impl ::std::error::Error for XargoTomlNotFound {}
+
+/// `Xargo.toml` was found but couldn't be parsed as TOML.
+#[derive(Debug)]
+pub struct TomlParse {
+    pub path: PathBuf,
+    /// The underlying parser's own message (line/column, bad token, etc.),
+    /// kept instead of discarded so the friendly message still points at
+    /// what's actually wrong.
+    pub cause: String,
+}
+
+impl fmt::Display for TomlParse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "could not parse `{}` as TOML: {}",
+            self.path.display(),
+            self.cause
+        )
+    }
+}
+
+impl ::std::error::Error for TomlParse {}
+
+/// `package.rust-src` was declared but couldn't be resolved to an actual
+/// `rust-src` checkout, whether as a local path, a rustup component, or a
+/// fetchable version/channel.
+#[derive(Debug)]
+pub struct RustSrcMissing {
+    pub declared: PathBuf,
+}
+
+impl fmt::Display for RustSrcMissing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "`package.rust-src` is set to `{}`, but no rust-src could be found or fetched for it",
+            self.declared.display()
+        )
+    }
+}
+
+impl ::std::error::Error for RustSrcMissing {}
+
+error_chain! {
+    foreign_links {
+        Io(::std::io::Error);
+        XargoTomlNotFound(XargoTomlNotFound);
+        TomlParse(TomlParse);
+        RustSrcMissing(RustSrcMissing);
+    }
+}
+
+/// Renders `error` as a single cargo-style line, picking the message up
+/// from the most specific typed cause in the chain rather than the default
+/// (possibly multi-line) `chain_err` context.
+pub fn render(error: &Error) -> String {
+    for cause in error.iter() {
+        if let Some(e) = cause.downcast_ref::<XargoTomlNotFound>() {
+            return format!("error: {}", e);
+        }
+
+        if let Some(e) = cause.downcast_ref::<TomlParse>() {
+            return format!("error: {}", e);
+        }
+
+        if let Some(e) = cause.downcast_ref::<RustSrcMissing>() {
+            return format!("error: {}", e);
+        }
+    }
+
+    format!("error: {}", error)
+}
+
+/// The process exit code to use for `error`, distinguishing "expected"
+/// configuration problems (1) from everything else (101, matching rustc's
+/// own convention for unexpected failures).
+pub fn exit_code(error: &Error) -> i32 {
+    for cause in error.iter() {
+        if cause.downcast_ref::<XargoTomlNotFound>().is_some()
+            || cause.downcast_ref::<TomlParse>().is_some()
+            || cause.downcast_ref::<RustSrcMissing>().is_some()
+        {
+            return 1;
+        }
+    }
+
+    101
+}
@@ -0,0 +1,130 @@
+//! A thin wrapper around a directory plus an advisory lock file inside it,
+//! used to guard xargo's per-target sysroot directories against concurrent
+//! writers. Adapted from cargo's own `flock` module.
+
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Display, Path, PathBuf};
+
+use fs2::FileExt;
+
+use errors::*;
+
+/// How chatty xargo should be about file-locking and command invocation,
+/// mirroring cargo's own `-q`/`-v`/`-vv` contract.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+    VeryVerbose,
+}
+
+impl Verbosity {
+    /// Parses `-q`/`--quiet` and repeated `-v`/`--verbose` (plus the
+    /// combined `-vv`) out of a raw argument list, the same contract cargo
+    /// itself follows. `-q` wins over any `-v` also present: xargo just
+    /// resolves to the quietest level asked for rather than rejecting the
+    /// combination the way cargo's own CLI does.
+    pub fn from_args<I, S>(args: I) -> Verbosity
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<::std::ffi::OsStr>,
+    {
+        let mut quiet = false;
+        let mut level = 0u32;
+
+        for arg in args {
+            match arg.as_ref().to_str() {
+                Some("-q") | Some("--quiet") => quiet = true,
+                Some("-v") | Some("--verbose") => level += 1,
+                Some("-vv") => level += 2,
+                _ => {}
+            }
+        }
+
+        if quiet {
+            Verbosity::Quiet
+        } else {
+            match level {
+                0 => Verbosity::Normal,
+                1 => Verbosity::Verbose,
+                _ => Verbosity::VeryVerbose,
+            }
+        }
+    }
+}
+
+/// A directory on disk that may be locked via a `.sentinel` file inside it.
+#[derive(Clone)]
+pub struct Filesystem {
+    root: PathBuf,
+    verbosity: Verbosity,
+}
+
+/// An open, locked file handle. The lock is released when this is dropped.
+pub struct FileLock {
+    file: File,
+}
+
+impl Filesystem {
+    pub fn new(root: PathBuf) -> Filesystem {
+        Filesystem { root, verbosity: Verbosity::Normal }
+    }
+
+    pub fn set_verbosity(&mut self, verbosity: Verbosity) {
+        self.verbosity = verbosity;
+    }
+
+    pub fn join<P: AsRef<Path>>(&self, path: P) -> Filesystem {
+        Filesystem {
+            root: self.root.join(path),
+            verbosity: self.verbosity,
+        }
+    }
+
+    pub fn display(&self) -> Display {
+        self.root.display()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.root
+    }
+
+    fn open(
+        &self,
+        name: &str,
+        description: &str,
+        try_lock: fn(&File) -> io::Result<()>,
+        lock: fn(&File) -> io::Result<()>,
+    ) -> Result<FileLock> {
+        fs::create_dir_all(&self.root)
+            .chain_err(|| format!("failed to create directory `{}`", self.root.display()))?;
+
+        let path = self.root.join(name);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(&path)
+            .chain_err(|| format!("failed to open `{}`", path.display()))?;
+
+        if try_lock(&file).is_err() {
+            if self.verbosity != Verbosity::Quiet {
+                eprintln!("waiting for file lock on {}", description);
+            }
+            lock(&file)
+                .chain_err(|| format!("failed to lock `{}`", path.display()))?;
+        }
+
+        Ok(FileLock { file })
+    }
+
+    pub fn open_ro(&self, name: &str, description: &str) -> Result<FileLock> {
+        self.open(name, description, File::try_lock_shared, File::lock_shared)
+    }
+
+    pub fn open_rw(&self, name: &str, description: &str) -> Result<FileLock> {
+        self.open(name, description, File::try_lock_exclusive, File::lock_exclusive)
+    }
+}
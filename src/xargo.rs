@@ -1,6 +1,6 @@
 use std::path::{Display, Path, PathBuf};
-use std::process::ExitStatus;
-use std::{env, mem};
+use std::process::{self, ExitStatus};
+use std::{env, fs, mem};
 use std::io::{self, Write};
 
 use toml::Value;
@@ -9,40 +9,65 @@ use rustc_version::VersionMeta;
 use CompilationMode;
 use cargo::{Config, Root, Rustflags, Subcommand};
 use cli::Args;
+use errors;
 use errors::*;
 use extensions::CommandExt;
 use flock::{FileLock, Filesystem};
+pub use flock::Verbosity;
 use {cargo, util};
 use rustc::Src;
 
+/// Checks for the `--emit-rust-project-json` opt-in flag, the same way
+/// `Verbosity::from_args` picks `-q`/`-v` out of a raw argument list: by
+/// scanning `args.all()` directly rather than through a parsed `cli` flag.
+fn wants_rust_project_json<I, S>(args: I) -> bool
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<::std::ffi::OsStr>,
+{
+    args.into_iter()
+        .any(|arg| arg.as_ref() == "--emit-rust-project-json")
+}
+
 pub fn run(
     args: &Args,
     cmode: &CompilationMode,
     rustflags: Rustflags,
+    root: &Root,
     home: &Home,
     meta: &VersionMeta,
     config: Option<&Config>,
-    verbose: bool,
 ) -> Result<ExitStatus> {
+    let verbosity = Verbosity::from_args(args.all());
+
+    if wants_rust_project_json(args.all()) {
+        if let Some(src) = toml_src(root, home)? {
+            rust_project_json(root, home, cmode, &src)?;
+        }
+    }
+
     let mut cmd = cargo::command();
     cmd.args(args.all());
 
     if args.subcommand() == Some(Subcommand::Doc) {
-        cmd.env(
-            "RUSTDOCFLAGS",
-            cargo::rustdocflags(config, cmode.triple())?.for_xargo(home),
-        );
+        let docflags = cargo::rustdocflags(config, cmode.triple())?
+            .extend(Rustflags::from_env("RUSTDOCFLAGS"))
+            .for_xargo(home);
+        cmd.env("RUSTDOCFLAGS", docflags);
     }
 
-    let flags = rustflags.for_xargo(home);
-    if verbose {
+    let flags = cargo::rustflags(config, cmode.triple())?
+        .extend(Rustflags::from_env("RUSTFLAGS"))
+        .extend(rustflags)
+        .for_xargo(home);
+    if verbosity >= Verbosity::Verbose {
         writeln!(io::stderr(), "+ RUSTFLAGS={:?}", flags).ok();
     }
     cmd.env("RUSTFLAGS", flags);
 
     let locks = (home.lock_ro(&meta.host), home.lock_ro(cmode.triple()));
 
-    let status = cmd.run_and_get_status(verbose)?;
+    let status = cmd.run_and_get_status(verbosity >= Verbosity::Verbose)?;
 
     mem::drop(locks);
 
@@ -62,6 +87,12 @@ impl Home {
         self.path.join("lib/rustlib").join(triple)
     }
 
+    /// The cache directory a fetched `rust-src` archive for `spec` is
+    /// unpacked into.
+    fn rust_src_cache(&self, spec: &str) -> Filesystem {
+        self.path.join("rust-src").join(spec)
+    }
+
     pub fn lock_ro(&self, triple: &str) -> Result<FileLock> {
         let fs = self.path(triple);
 
@@ -79,9 +110,147 @@ impl Home {
                 format!("couldn't lock {}'s sysroot as read-only", triple)
             })
     }
+
+    /// Records the rustc commit hash `triple`'s sysroot was built against,
+    /// so `sysroots()` can report it later. Should be called once a sysroot
+    /// build for `triple` succeeds.
+    pub fn record_rustc_hash(&self, triple: &str, meta: &VersionMeta) -> Result<()> {
+        let dir = self.path(triple);
+        let hash = meta.commit_hash.as_deref().unwrap_or("unknown");
+
+        fs::write(dir.path().join(".rustc-hash"), hash)
+            .chain_err(|| format!("couldn't record the rustc hash for {}'s sysroot", triple))
+    }
+
+    /// Enumerates every per-target sysroot cached under this `Home`, e.g.
+    /// for `xargo sysroot`.
+    pub fn sysroots(&self) -> Result<Vec<SysrootInfo>> {
+        let rustlib = self.path.join("lib/rustlib");
+        let mut out = Vec::new();
+
+        let entries = match fs::read_dir(rustlib.path()) {
+            Ok(entries) => entries,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(out),
+            Err(e) => {
+                return Err(e).chain_err(|| "couldn't read the sysroot cache")
+            }
+        };
+
+        let targets = rustc_target_list().ok();
+
+        for entry in entries {
+            let entry = entry.chain_err(|| "couldn't read a sysroot cache entry")?;
+
+            if !entry
+                .file_type()
+                .chain_err(|| "couldn't stat a sysroot cache entry")?
+                .is_dir()
+            {
+                continue;
+            }
+
+            let triple = entry.file_name().to_string_lossy().into_owned();
+            let size = dir_size(&entry.path())?;
+            let rustc_hash = fs::read_to_string(entry.path().join(".rustc-hash")).ok();
+            let known = targets
+                .as_ref()
+                .map_or(true, |targets| targets.iter().any(|known| known == &triple));
+
+            out.push(SysrootInfo { triple, size, rustc_hash, known });
+        }
+
+        Ok(out)
+    }
+
+    /// Removes the cached sysroot for `triple`, or every cached sysroot when
+    /// `triple` is `None`. Takes the write lock first so this can't race a
+    /// concurrent build of the same sysroot.
+    pub fn clean(&self, triple: Option<&str>) -> Result<()> {
+        match triple {
+            Some(triple) => {
+                if !is_known_target(triple)? {
+                    return Err(format!("`{}` is not a target rustc knows about", triple).into());
+                }
+
+                let _lock = self.lock_rw(triple)?;
+                remove_dir(self.path(triple).path())
+            }
+            None => {
+                for info in self.sysroots()? {
+                    let _lock = self.lock_rw(&info.triple)?;
+                    remove_dir(self.path(&info.triple).path())?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Metadata about one cached sysroot, as reported by `xargo sysroot`.
+pub struct SysrootInfo {
+    pub triple: String,
+    pub size: u64,
+    pub rustc_hash: Option<String>,
+    /// Whether `triple` is still a target rustc recognizes. A cached
+    /// sysroot for a target that rustc has since dropped is otherwise
+    /// invisible to `xargo sysroot --clean <triple>`'s validation.
+    pub known: bool,
+}
+
+fn dir_size(path: &Path) -> Result<u64> {
+    let mut total = 0;
+
+    for entry in fs::read_dir(path).chain_err(|| format!("couldn't read `{}`", path.display()))? {
+        let entry = entry.chain_err(|| format!("couldn't read `{}`", path.display()))?;
+        let meta = entry
+            .metadata()
+            .chain_err(|| format!("couldn't stat `{}`", entry.path().display()))?;
+
+        total += if meta.is_dir() {
+            dir_size(&entry.path())?
+        } else {
+            meta.len()
+        };
+    }
+
+    Ok(total)
+}
+
+fn remove_dir(path: &Path) -> Result<()> {
+    fs::remove_dir_all(path).chain_err(|| format!("couldn't remove `{}`", path.display()))
 }
 
-pub fn home(cmode: &CompilationMode) -> Result<Home> {
+/// The triples `rustc --print target-list` knows about.
+fn rustc_target_list() -> Result<Vec<String>> {
+    let out = process::Command::new("rustc")
+        .args(&["--print", "target-list"])
+        .output()
+        .chain_err(|| "couldn't run `rustc --print target-list`")?;
+
+    if !out.status.success() {
+        return Err(format!(
+            "`rustc --print target-list` exited with {}: {}",
+            out.status,
+            String::from_utf8_lossy(&out.stderr)
+        ).into());
+    }
+
+    let list = String::from_utf8_lossy(&out.stdout);
+
+    Ok(list.lines().map(str::to_owned).collect())
+}
+
+/// Checks that `triple` is a target rustc actually knows about. Used to
+/// validate the argument to `xargo sysroot --clean <triple>` before
+/// touching the cache.
+pub fn is_known_target(triple: &str) -> Result<bool> {
+    Ok(rustc_target_list()?.iter().any(|known| known == triple))
+}
+
+pub fn home(cmode: &CompilationMode, args: &Args) -> Result<Home> {
+    let verbosity = Verbosity::from_args(args.all());
+
     let mut p = if let Some(h) = env::var_os("XARGO_HOME") {
         PathBuf::from(h)
     } else {
@@ -94,9 +263,10 @@ pub fn home(cmode: &CompilationMode) -> Result<Home> {
         p.push("HOST");
     }
 
-    Ok(Home {
-        path: Filesystem::new(p),
-    })
+    let mut path = Filesystem::new(p);
+    path.set_verbosity(verbosity);
+
+    Ok(Home { path })
 }
 
 pub struct Toml {
@@ -130,27 +300,140 @@ impl Toml {
 /// content of this 'Xargo.toml'
 pub fn toml(root: &Root) -> Result<(Option<&Path>, Option<Toml>)> {
     if let Some(p) = util::search(root.path(), "Xargo.toml") {
-        Ok((Some(p), util::parse(&p.join("Xargo.toml")).map(|t| Some(Toml { table: t }))?))
+        let table = util::parse(&p.join("Xargo.toml")).map_err(|e| {
+            errors::TomlParse { path: p.join("Xargo.toml"), cause: e.to_string() }
+        })?;
+
+        Ok((Some(p), Some(Toml { table })))
     }
     else {
         Ok((None, None))
     }
 }
 
-/// Returns the closest directory containing a 'Xargo.toml' and the parsed
-/// content of this 'Xargo.toml'
-pub fn toml_src(root: &Root) -> Result<Option<Src>> {
-    Ok(if let Some(toml) = toml(root)?.1 {
+/// Like [`toml`], but treats a missing `Xargo.toml` as an error instead of
+/// `Ok((None, None))`. For callers (e.g. [`rust_project_json`]) that have
+/// nothing useful to do without one.
+pub fn require_toml(root: &Root) -> Result<(PathBuf, Toml)> {
+    match toml(root)? {
+        (Some(path), Some(toml)) => Ok((path.to_owned(), toml)),
+        _ => Err(errors::XargoTomlNotFound { searched_at: root.path().to_owned() }.into()),
+    }
+}
+
+/// A sysroot crate discovered under `rust-src`, e.g. `core` or `alloc`.
+struct SysrootCrate {
+    name: String,
+    root_module: PathBuf,
+    deps: Vec<String>,
+}
+
+/// Crates that make up a `no_std` sysroot, in dependency order. `std` is
+/// only included for native (non-`no_std`) sysroots; no crate that depends
+/// on `std` is included.
+fn sysroot_crates(src: &Src, cmode: &CompilationMode) -> Vec<SysrootCrate> {
+    let mut crates = vec![
+        SysrootCrate { name: "core".to_owned(), root_module: src.path().join("core/src/lib.rs"), deps: vec![] },
+        SysrootCrate { name: "compiler_builtins".to_owned(), root_module: src.path().join("compiler_builtins/src/lib.rs"), deps: vec!["core".to_owned()] },
+        SysrootCrate { name: "alloc".to_owned(), root_module: src.path().join("alloc/src/lib.rs"), deps: vec!["core".to_owned(), "compiler_builtins".to_owned()] },
+    ];
+
+    if cmode.is_native() {
+        crates.push(SysrootCrate {
+            name: "std".to_owned(),
+            root_module: src.path().join("std/src/lib.rs"),
+            deps: vec!["core".to_owned(), "alloc".to_owned(), "compiler_builtins".to_owned()],
+        });
+    }
+
+    crates.retain(|krate| krate.root_module.exists());
+    crates
+}
+
+fn json_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+fn json_string(s: &str) -> String {
+    format!("\"{}\"", json_escape(s))
+}
+
+/// Writes a `rust-project.json` describing xargo's sysroot at `root`, so
+/// that rust-analyzer resolves `core`/`alloc`/`std` against the exact
+/// cross-compiled sysroot xargo produced instead of the host's.
+pub fn rust_project_json(
+    root: &Root,
+    home: &Home,
+    cmode: &CompilationMode,
+    src: &Src,
+) -> Result<()> {
+    // Nothing useful to describe without an Xargo.toml declaring the
+    // sysroot dependencies this is meant to help rust-analyzer resolve.
+    require_toml(root)?;
+
+    let krates = sysroot_crates(src, cmode);
+
+    let mut crates_json = String::new();
+    for (i, krate) in krates.iter().enumerate() {
+        if i > 0 {
+            crates_json.push(',');
+        }
+
+        // A dep can be missing from `krates` when `retain` above dropped it
+        // (e.g. a partial rust-src checkout with `alloc` but no
+        // `compiler_builtins`); skip those rather than pointing at a crate
+        // index that was never emitted.
+        let deps_json = krate
+            .deps
+            .iter()
+            .filter_map(|dep| {
+                krates
+                    .iter()
+                    .position(|k| &k.name == dep)
+                    .map(|idx| format!("{{\"crate\":{},\"name\":{}}}", idx, json_string(dep)))
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+
+        crates_json.push_str(&format!(
+            "{{\"root_module\":{},\"edition\":\"2018\",\"deps\":[{}],\"cfg\":[\"feature=\\\"rustc-dep-of-std\\\"\"]}}",
+            json_string(&krate.root_module.display().to_string()),
+            deps_json,
+        ));
+    }
+
+    let json = format!(
+        "{{\"sysroot\":{},\"sysroot_src\":{},\"crates\":[{}]}}",
+        json_string(&home.display().to_string()),
+        json_string(&src.path().display().to_string()),
+        crates_json,
+    );
+
+    fs::write(root.path().join("rust-project.json"), json)
+        .chain_err(|| "couldn't write rust-project.json")?;
+
+    Ok(())
+}
+
+/// Resolves `package.rust-src`, trying in order: a local path, the
+/// `rust-src` rustup component on the active toolchain, a version/channel
+/// spec that's fetched and cached under `home`. A declared value that none
+/// of these resolve is an error. Only when `package.rust-src` isn't declared
+/// is the `XARGO_RUST_SRC` environment variable used.
+pub fn toml_src(root: &Root, home: &Home) -> Result<Option<Src>> {
+    let declared = if let Some(toml) = toml(root)?.1 {
         if let Some(Value::Table(table)) = toml.package() {
-            if let Some(src) = table.get("rust-src").map(Value::as_str).flatten() {
-                let src = src.split("/").collect::<Vec<_>>().join(&std::path::MAIN_SEPARATOR.to_string());
-                if let Some(path) = PathBuf::from(src).canonicalize().ok() {
-                    dbg!(&path);
-                    Some(Src::from(path))
-                } else {
-                    eprintln!("Warning: package.rust-src key exists but directory does not exist ");
-                    None
-                }
+            if let Some(spec) = table.get("rust-src").map(Value::as_str).flatten() {
+                Some(resolve_rust_src(spec, home)?)
             } else {
                 None
             }
@@ -159,5 +442,131 @@ pub fn toml_src(root: &Root) -> Result<Option<Src>> {
         }
     } else {
         None
-    })
+    };
+
+    if declared.is_some() {
+        return Ok(declared);
+    }
+
+    // No `package.rust-src` declared; fall back to the env var, if set.
+    if let Some(src) = env::var_os("XARGO_RUST_SRC") {
+        return PathBuf::from(src)
+            .canonicalize()
+            .map(|path| Some(Src::from(path)))
+            .chain_err(|| "couldn't canonicalize $XARGO_RUST_SRC");
+    }
+
+    Ok(None)
+}
+
+/// Resolves a `package.rust-src` value: first as a local path, then as the
+/// `rust-src` component of the active rustup toolchain, then as a
+/// version/channel spec to download from static.rust-lang.org.
+fn resolve_rust_src(spec: &str, home: &Home) -> Result<Src> {
+    let local = spec
+        .split('/')
+        .collect::<Vec<_>>()
+        .join(&std::path::MAIN_SEPARATOR.to_string());
+
+    if let Ok(path) = PathBuf::from(local).canonicalize() {
+        return Ok(Src::from(path));
+    }
+
+    if let Some(path) = rustup_component_src(spec)? {
+        return Ok(Src::from(path));
+    }
+
+    fetch_rust_src(spec, home)
+}
+
+/// Looks for `rust-src` already installed as a rustup component of the
+/// *active* toolchain, under `$(rustc --print sysroot)/lib/rustlib/src/rust`
+/// — but only when `spec` doesn't pin a specific version/channel that the
+/// active toolchain might not actually be. `spec == "rust-src"` (no
+/// version given) always matches; otherwise `spec` must name the active
+/// toolchain itself.
+fn rustup_component_src(spec: &str) -> Result<Option<PathBuf>> {
+    if spec != "rust-src" && !is_active_toolchain(spec)? {
+        return Ok(None);
+    }
+
+    let out = process::Command::new("rustc")
+        .args(&["--print", "sysroot"])
+        .output()
+        .chain_err(|| "couldn't run `rustc --print sysroot`")?;
+
+    let sysroot = PathBuf::from(String::from_utf8_lossy(&out.stdout).trim());
+    let src = sysroot.join("lib/rustlib/src/rust/library");
+
+    Ok(if src.is_dir() { Some(src) } else { None })
+}
+
+/// Whether `spec` names the toolchain `rustup` would currently pick, e.g.
+/// `spec = "nightly"` when `rustup show active-toolchain` reports
+/// `nightly-x86_64-unknown-linux-gnu (default)`. Returns `false` (rather
+/// than erroring) when `rustup` itself isn't available, since that just
+/// means this source can't confirm a match, not that `spec` is wrong.
+fn is_active_toolchain(spec: &str) -> Result<bool> {
+    let out = match process::Command::new("rustup")
+        .args(&["show", "active-toolchain"])
+        .output()
+    {
+        Ok(out) if out.status.success() => out,
+        _ => return Ok(false),
+    };
+
+    let toolchain = String::from_utf8_lossy(&out.stdout);
+    Ok(toolchain.split_whitespace().next().map_or(false, |name| {
+        name == spec || name.starts_with(&format!("{}-", spec))
+    }))
+}
+
+/// Downloads the `rust-src` archive for `spec` (a version number or channel
+/// name, e.g. `1.70.0` or `nightly`) and unpacks it into a cache directory
+/// under `home`, reusing an existing unpack on subsequent calls.
+fn fetch_rust_src(spec: &str, home: &Home) -> Result<Src> {
+    let cache = home.rust_src_cache(spec);
+    // The dist tarball nests its contents under a top-level
+    // `rust-src-<spec>/` directory rather than unpacking flat.
+    let library = cache
+        .path()
+        .join(format!("rust-src-{}", spec))
+        .join("rust-src/lib/rustlib/src/rust/library");
+
+    if !library.is_dir() {
+        fs::create_dir_all(cache.path())
+            .chain_err(|| format!("couldn't create `{}`", cache.path().display()))?;
+
+        let url = format!(
+            "https://static.rust-lang.org/dist/{}/rust-src-{}.tar.gz",
+            spec, spec
+        );
+        let archive = cache.path().join("rust-src.tar.gz");
+
+        let ok = process::Command::new("curl")
+            .args(&["-sSfL", "-o"])
+            .arg(&archive)
+            .arg(&url)
+            .status()
+            .chain_err(|| format!("couldn't download `{}`", url))?
+            .success();
+
+        if !ok {
+            return Err(errors::RustSrcMissing { declared: PathBuf::from(spec) }.into());
+        }
+
+        process::Command::new("tar")
+            .args(&["xf"])
+            .arg(&archive)
+            .arg("-C")
+            .arg(cache.path())
+            .status()
+            .chain_err(|| "couldn't unpack the rust-src archive")?;
+
+        if !library.is_dir() {
+            return Err(errors::RustSrcMissing { declared: PathBuf::from(spec) }.into());
+        }
+    }
+
+    Ok(Src::from(library))
 }